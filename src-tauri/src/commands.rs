@@ -3,10 +3,25 @@ use std::fs::File;
 use std::io::Write;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use tokio::sync::oneshot;
 use std::path::PathBuf;
 use tauri::command;
 use tauri::api::dialog::FileDialogBuilder;
+use tauri::State;
+
+use crate::config::{self, EngineConfig, EngineConfigData};
+use crate::error::WorkerError;
+use crate::jobs::{self, JobRegistry};
+use crate::worker::AiWorker;
+
+/// Wall-clock budget for a single AI worker call. A page can legitimately
+/// take a while on a slow model, but a hung call should still be caught.
+const PAGE_TIMEOUT: Duration = Duration::from_secs(120);
+/// Audio transcripts run longer than a single page, so they get more room.
+const AUDIO_TIMEOUT: Duration = Duration::from_secs(600);
+/// Budget for the one-off PDF export script.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(300);
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct RedactionSuggestion {
@@ -44,57 +59,27 @@ pub struct InitializationStatus {
 }
 
 #[command]
-pub async fn initialize_ai_engine() -> Result<InitializationStatus, String> {
-    // Get the Python executable path
-    let python_exe = if cfg!(windows) {
-        "C:/coding/gemma/.venv/Scripts/python.exe"
-    } else {
-        "python3"
-    };
+pub async fn initialize_ai_engine(
+    worker: State<'_, AiWorker>,
+    config: State<'_, EngineConfig>,
+) -> Result<InitializationStatus, WorkerError> {
+    let config = config.snapshot();
+
+    // Spawn the persistent worker once; subsequent calls are no-ops and just
+    // reuse the already-resident model.
+    worker.ensure_started(
+        &config.python_exe.to_string_lossy(),
+        "python-worker/worker.py",
+        &config.worker_dir.to_string_lossy(),
+        config.resource_limits(),
+    )?;
+
+    worker.call("initialize", serde_json::json!({}), PAGE_TIMEOUT, |_progress| {})
+}
 
-    // Call the AI initialization script
-    let mut cmd = Command::new(python_exe);
-    cmd.arg("python-worker/initialize_ai.py")
-        .current_dir("C:/coding/gemma");
-    
-    // Hide console window on Windows
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-    }
-    
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute AI initialization script: {}", e))?;
-
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        
-        // Print debug output
-        if !stderr.is_empty() {
-            println!("AI init debug output:\n{}", stderr);
-        }
-        
-        // Parse the last line as JSON status
-        if let Some(last_line) = stdout.lines().last() {
-            match serde_json::from_str::<InitializationStatus>(last_line) {
-                Ok(status) => Ok(status),
-                Err(_) => Ok(InitializationStatus {
-                    status: "ready".to_string(),
-                    message: "AI engine initialized successfully".to_string(),
-                })
-            }
-        } else {
-            Ok(InitializationStatus {
-                status: "ready".to_string(),
-                message: "AI engine initialized successfully".to_string(),
-            })
-        }
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("AI initialization failed: {}", stderr))
-    }
+#[command]
+pub async fn shutdown_ai_engine(worker: State<'_, AiWorker>) -> Result<(), String> {
+    worker.shutdown()
 }
 
 #[command]
@@ -118,77 +103,92 @@ pub async fn save_temp_file(file_name: String, file_data: Vec<u8>) -> Result<Str
 }
 
 #[command]
-pub async fn start_document_processing(file_path: String, total_pages: i32, profile: String) -> Result<(), String> {
-    // This will be called to initiate the processing
-    // We'll implement the page-by-page processing logic here
-    println!("Starting document processing for: {} with {} pages using {} profile", file_path, total_pages, profile);
-    Ok(())
+pub async fn start_document_processing(
+    jobs: State<'_, JobRegistry>,
+    file_path: String,
+    total_pages: i32,
+    profile: String,
+) -> Result<String, String> {
+    let job_id = jobs.new_job_id();
+    println!(
+        "Starting document processing job {} for: {} with {} pages using {} profile",
+        job_id, file_path, total_pages, profile
+    );
+    Ok(job_id)
 }
 
 #[command]
-pub async fn process_single_page(file_path: String, page_number: i32, profile: String) -> Result<Vec<RedactionSuggestion>, String> {
-    // Get the Python executable path
-    let python_exe = if cfg!(windows) {
-        "C:/coding/gemma/.venv/Scripts/python.exe"
-    } else {
-        "python3"
-    };
-
-    // Call Python worker script for a single page
-    let mut cmd = Command::new(python_exe);
-    cmd.arg("python-worker/process_page.py")
-        .arg(&file_path)
-        .arg(&page_number.to_string())
-        .arg(&profile)
-        .current_dir("C:/coding/gemma"); // Set working directory
-    
-    // Hide console window on Windows
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-    }
-    
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute Python script: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        println!("Python script stderr: {}", stderr);
-        return Err(format!("Python script failed: {}", stderr));
-    }
+pub async fn process_single_page(
+    window: tauri::Window,
+    worker: State<'_, AiWorker>,
+    config: State<'_, EngineConfig>,
+    jobs: State<'_, JobRegistry>,
+    job_id: String,
+    file_path: String,
+    page_number: i32,
+    profile: String,
+) -> Result<Vec<RedactionSuggestion>, WorkerError> {
+    let config = config.snapshot();
+    worker.ensure_started(
+        &config.python_exe.to_string_lossy(),
+        "python-worker/worker.py",
+        &config.worker_dir.to_string_lossy(),
+        config.resource_limits(),
+    )?;
+
+    jobs.register(&job_id).map_err(|message| WorkerError::Busy { message })?;
+    let result = worker.call(
+        "process_page",
+        serde_json::json!({
+            "file_path": file_path,
+            "page_number": page_number,
+            "profile": profile,
+        }),
+        PAGE_TIMEOUT,
+        |progress| {
+            let _ = window.emit("redaction-progress", progress);
+        },
+    );
+    jobs.unregister(&job_id);
+
+    result
+}
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    
-    // Print stderr for debugging purposes
-    if !stderr.is_empty() {
-        println!("Python script debug output:\n{}", stderr);
+#[command]
+pub async fn cancel_processing(
+    worker: State<'_, AiWorker>,
+    jobs: State<'_, JobRegistry>,
+    job_id: String,
+) -> Result<(), String> {
+    if !jobs.is_running(&job_id) {
+        return Err(format!("Job {} is not currently running", job_id));
     }
-    
-    // Clean up JSON response (remove trailing commas that might cause parsing issues)
-    let cleaned_stdout = stdout
-        .replace(",\n]", "\n]")
-        .replace(",\n}", "\n}");
-    
-    let suggestions: Vec<RedactionSuggestion> = serde_json::from_str(&cleaned_stdout)
-        .map_err(|e| format!("Failed to parse JSON output: {}. Raw output: {}", e, stdout))?;
 
-    Ok(suggestions)
+    worker.cancel(jobs::DEFAULT_STOP_TIMEOUT)?;
+    jobs.unregister(&job_id);
+    Ok(())
 }
 
+/// Neither this nor `export_redacted_audio` take a `job_id` or register
+/// with `JobRegistry`: both run a one-off script through
+/// `worker::run_streaming_script`, which keeps no externally-reachable
+/// handle to the child it spawns, so there's nothing `cancel_processing`
+/// could actually cancel. Keep them consistent on that point rather than
+/// having one claim cancellability it can't deliver.
 #[command]
 pub async fn export_redacted_document(
-    file_path: String, 
-    redactions: Vec<RedactionSuggestion>, 
+    window: tauri::Window,
+    config: State<'_, EngineConfig>,
+    file_path: String,
+    redactions: Vec<RedactionSuggestion>,
     suggested_filename: String
-) -> Result<String, String> {
+) -> Result<String, WorkerError> {
     use std::sync::{Arc, Mutex};
     use tokio::sync::oneshot;
-    
+
     let (tx, rx) = oneshot::channel();
     let tx = Arc::new(Mutex::new(Some(tx)));
-    
+
     // Show save dialog with callback
     FileDialogBuilder::new()
         .set_title("Save Redacted PDF")
@@ -200,61 +200,48 @@ pub async fn export_redacted_document(
                 let _ = tx.send(path);
             }
         });
-    
+
     // Wait for dialog result
-    let save_path = rx.await.map_err(|_| "Dialog callback failed".to_string())?;
-    
+    let save_path = rx.await.map_err(|_| WorkerError::DialogCancelled {
+        message: "Dialog callback failed".to_string(),
+    })?;
+
     let output_path = match save_path {
         Some(path) => path.to_string_lossy().to_string(),
-        None => return Err("Save dialog was cancelled".to_string()),
+        None => {
+            return Err(WorkerError::DialogCancelled {
+                message: "Save dialog was cancelled".to_string(),
+            })
+        }
     };
 
-    // Get the Python executable path
-    let python_exe = if cfg!(windows) {
-        "C:/coding/gemma/.venv/Scripts/python.exe"
-    } else {
-        "python3"
-    };
+    let config = config.snapshot();
 
     // Call Python script to export the final redacted PDF
-    let redactions_json = serde_json::to_string(&redactions)
-        .map_err(|e| format!("Failed to serialize redactions: {}", e))?;
+    let redactions_json =
+        serde_json::to_string(&redactions).map_err(|e| WorkerError::SpawnFailed {
+            message: format!("Failed to serialize redactions: {}", e),
+        })?;
 
-    let mut cmd = Command::new(python_exe);
+    let mut cmd = Command::new(&config.python_exe);
     cmd.arg("python-worker/export_pdf.py")
         .arg(&file_path)
         .arg(&redactions_json)
         .arg(&output_path)
-        .current_dir("C:/coding/gemma"); // Set working directory
-    
+        .current_dir(&config.worker_dir); // Set working directory
+
     // Hide console window on Windows
     #[cfg(windows)]
     {
         use std::os::windows::process::CommandExt;
         cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
     }
-    
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute Python export script: {}", e))?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        println!("Export script failed - stderr: {}", stderr);
-        println!("Export script failed - stdout: {}", stdout);
-        return Err(format!("Export script failed: {}", stderr));
-    }
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    
-    // Print debug output
-    if !stderr.is_empty() {
-        println!("Export script debug output:\n{}", stderr);
-    }
-    if !stdout.is_empty() {
-        println!("Export script stdout:\n{}", stdout);
-    }
+    // Stream progress records as pages are written instead of blocking until
+    // the whole document finishes exporting.
+    crate::worker::run_streaming_script::<()>(cmd, config.resource_limits(), EXPORT_TIMEOUT, |progress| {
+        let _ = window.emit("redaction-progress", progress);
+    })?;
 
     Ok(output_path)
 }
@@ -278,55 +265,49 @@ pub async fn save_temp_audio(file_name: String, file_data: Vec<u8>) -> Result<St
 }
 
 #[command]
-pub async fn process_audio(audio_path: String) -> Result<String, String> {
-    // Get the Python executable path
-    let python_exe = if cfg!(windows) {
-        "C:/coding/gemma/.venv/Scripts/python.exe"
-    } else {
-        "python3"
-    };
-
-    // Run the audio processing script
-    let mut cmd = Command::new(python_exe);
-    cmd.arg("python-worker/process_audio.py")
-        .arg(&audio_path)
-        .current_dir("C:/coding/gemma");
-    
-    // Hide console window on Windows
-    #[cfg(windows)]
-    {
-        use std::os::windows::process::CommandExt;
-        cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
-    }
-    
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute audio processing script: {}", e))?;
-
-    if output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        
-        // Print debug output
-        if !stderr.is_empty() {
-            println!("Audio processing debug output:\n{}", stderr);
-        }
-        
-        Ok(stdout.to_string())
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        Err(format!("Audio processing failed: {}", stderr))
-    }
+pub async fn process_audio(
+    window: tauri::Window,
+    worker: State<'_, AiWorker>,
+    config: State<'_, EngineConfig>,
+    jobs: State<'_, JobRegistry>,
+    job_id: String,
+    audio_path: String,
+) -> Result<String, WorkerError> {
+    let config = config.snapshot();
+    worker.ensure_started(
+        &config.python_exe.to_string_lossy(),
+        "python-worker/worker.py",
+        &config.worker_dir.to_string_lossy(),
+        config.resource_limits(),
+    )?;
+
+    jobs.register(&job_id).map_err(|message| WorkerError::Busy { message })?;
+    let result = worker.call(
+        "process_audio",
+        serde_json::json!({ "audio_path": audio_path }),
+        AUDIO_TIMEOUT,
+        |progress| {
+            let _ = window.emit("redaction-progress", progress);
+        },
+    );
+    jobs.unregister(&job_id);
+
+    result
 }
 
+/// See the note on `export_redacted_document` — this is the other half of
+/// that pair and deliberately has no `job_id`/`JobRegistry` either.
 #[command]
 pub async fn export_redacted_audio(
+    window: tauri::Window,
+    config: State<'_, EngineConfig>,
     original_path: String,
     redactions: String,  // JSON string of redactions
     output_name: String
-) -> Result<String, String> {
+) -> Result<String, WorkerError> {
     let (tx, rx) = oneshot::channel();
     let tx = Arc::new(Mutex::new(Some(tx)));
-    
+
     // Show save dialog
     FileDialogBuilder::new()
         .set_title("Save Redacted Audio")
@@ -342,62 +323,121 @@ pub async fn export_redacted_audio(
     // Wait for the user's selection
     let save_path = match rx.await {
         Ok(Some(path)) => path,
-        Ok(None) => return Err("Save cancelled by user".to_string()),
-        Err(_) => return Err("Failed to get save path".to_string()),
+        Ok(None) => {
+            return Err(WorkerError::DialogCancelled {
+                message: "Save cancelled by user".to_string(),
+            })
+        }
+        Err(_) => {
+            return Err(WorkerError::DialogCancelled {
+                message: "Failed to get save path".to_string(),
+            })
+        }
     };
-    
+
     let output_path = save_path.to_string_lossy().to_string();
 
-    // Get the Python executable path
-    let python_exe = if cfg!(windows) {
-        "C:/coding/gemma/.venv/Scripts/python.exe"
-    } else {
-        "python3"
-    };
+    let config = config.snapshot();
 
     // Create a temporary file for redactions data
     let temp_dir = std::env::temp_dir().join("guardian_redact");
-    std::fs::create_dir_all(&temp_dir)
-        .map_err(|e| format!("Failed to create temp directory: {}", e))?;
-    
+    std::fs::create_dir_all(&temp_dir).map_err(|e| WorkerError::SpawnFailed {
+        message: format!("Failed to create temp directory: {}", e),
+    })?;
+
     let redactions_file = temp_dir.join("redactions.json");
-    let mut file = File::create(&redactions_file)
-        .map_err(|e| format!("Failed to create redactions file: {}", e))?;
+    let mut file = File::create(&redactions_file).map_err(|e| WorkerError::SpawnFailed {
+        message: format!("Failed to create redactions file: {}", e),
+    })?;
     file.write_all(redactions.as_bytes())
-        .map_err(|e| format!("Failed to write redactions data: {}", e))?;
-
-    // Run the audio redaction script
-    let mut cmd = Command::new(python_exe);
+        .map_err(|e| WorkerError::SpawnFailed {
+            message: format!("Failed to write redactions data: {}", e),
+        })?;
+
+    // Run the audio redaction script through the same process-group-wrapped,
+    // resource-capped, timeout-bound path as every other worker subprocess,
+    // so a runaway ffmpeg invocation on untrusted input doesn't outlive a
+    // cancel or crash unnoticed.
+    let mut cmd = Command::new(&config.python_exe);
     cmd.arg("python-worker/apply_audio_redactions.py")
         .arg(&original_path)
         .arg(&redactions_file.to_string_lossy().to_string())
         .arg(&output_path)
-        .current_dir("C:/coding/gemma");
-    
+        .current_dir(&config.worker_dir);
+
     // Hide console window on Windows
     #[cfg(windows)]
     {
         use std::os::windows::process::CommandExt;
         cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
     }
-    
-    let output = cmd.output()
-        .map_err(|e| format!("Failed to execute audio redaction script: {}", e))?;
 
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    
-    // Always print debug output for audio processing
-    if !stderr.is_empty() {
-        println!("Audio redaction debug output:\n{}", stderr);
-    }
-    if !stdout.is_empty() {
-        println!("Audio redaction stdout:\n{}", stdout);
-    }
+    // Not registered with `JobRegistry`: this spawns its own one-off child
+    // that `cancel_processing` has no handle to, so it isn't cancellable —
+    // see the note on `JobRegistry` for why we don't pretend otherwise.
+    let result = crate::worker::run_streaming_script::<()>(
+        cmd,
+        config.resource_limits(),
+        AUDIO_TIMEOUT,
+        |progress| {
+            let _ = window.emit("redaction-progress", progress);
+        },
+    );
+
+    result.map(|()| output_path)
+}
 
-    if output.status.success() {
-        Ok(output_path)
-    } else {
-        Err(format!("Audio redaction failed: {}", stderr))
-    }
+/// Look for a python executable in the usual venv locations under the
+/// configured worker directory (and a couple of system-wide fallbacks), so
+/// the frontend can offer the user a pre-filled choice instead of asking
+/// them to type a path blind.
+#[command]
+pub async fn detect_python_runtime(config: State<'_, EngineConfig>) -> Result<Vec<String>, String> {
+    let worker_dir = config.snapshot().worker_dir;
+    Ok(config::detect_candidates(&worker_dir)
+        .into_iter()
+        .map(|path| path.to_string_lossy().to_string())
+        .collect())
+}
+
+/// Point the engine at a different python executable / worker directory.
+/// Validates the new config by actually starting a worker with it and
+/// running `initialize` before persisting, so a bad path is rejected with
+/// a real `WorkerError` instead of silently bricking the next launch.
+#[command]
+pub async fn set_engine_config(
+    app: tauri::AppHandle,
+    worker: State<'_, AiWorker>,
+    config: State<'_, EngineConfig>,
+    python_exe: String,
+    worker_dir: String,
+    profile: String,
+    max_address_space_bytes: Option<u64>,
+    max_cpu_seconds: Option<u64>,
+) -> Result<InitializationStatus, WorkerError> {
+    let data = EngineConfigData {
+        python_exe: PathBuf::from(python_exe),
+        worker_dir: PathBuf::from(worker_dir),
+        profile,
+        max_address_space_bytes,
+        max_cpu_seconds,
+    };
+
+    // A resident worker was started with the old config; drop it so the
+    // validation call below spawns a fresh one with the new paths.
+    let _ = worker.shutdown();
+    worker.ensure_started(
+        &data.python_exe.to_string_lossy(),
+        "python-worker/worker.py",
+        &data.worker_dir.to_string_lossy(),
+        data.resource_limits(),
+    )?;
+    let status: InitializationStatus =
+        worker.call("initialize", serde_json::json!({}), PAGE_TIMEOUT, |_progress| {})?;
+
+    config
+        .set(&app, data)
+        .map_err(|message| WorkerError::SpawnFailed { message })?;
+
+    Ok(status)
 }
@@ -0,0 +1,136 @@
+//! Runtime location of the Python AI worker. Early prototypes hardcoded
+//! `C:/coding/gemma/.venv/Scripts/python.exe` into every command, so the
+//! app only ran on one developer's machine. This resolves those paths once
+//! at startup (from a config file, with env overrides) and keeps them in
+//! Tauri-managed state so they can be changed without a rebuild.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::resource_limits::ResourceLimits;
+
+const CONFIG_FILE_NAME: &str = "engine_config.json";
+
+/// Python executable, worker script directory, active redaction profile,
+/// and the resource caps applied to worker subprocesses. Cloned out via
+/// `snapshot()` rather than handed out by reference, since commands hold it
+/// only briefly.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EngineConfigData {
+    pub python_exe: PathBuf,
+    pub worker_dir: PathBuf,
+    pub profile: String,
+    /// Address-space cap applied to worker subprocesses, so an operator
+    /// processing untrusted PDFs can bound how much memory a runaway model
+    /// invocation can consume. `None` means "no cap".
+    #[serde(default)]
+    pub max_address_space_bytes: Option<u64>,
+    /// CPU-time cap applied to worker subprocesses, same rationale.
+    #[serde(default)]
+    pub max_cpu_seconds: Option<u64>,
+}
+
+impl Default for EngineConfigData {
+    fn default() -> Self {
+        let python_exe = if cfg!(windows) {
+            PathBuf::from("C:/coding/gemma/.venv/Scripts/python.exe")
+        } else {
+            PathBuf::from("python3")
+        };
+        Self {
+            python_exe,
+            worker_dir: PathBuf::from("C:/coding/gemma"),
+            profile: "default".to_string(),
+            max_address_space_bytes: None,
+            max_cpu_seconds: None,
+        }
+    }
+}
+
+impl EngineConfigData {
+    /// The `ResourceLimits` to apply to worker subprocesses spawned under
+    /// this config.
+    pub fn resource_limits(&self) -> ResourceLimits {
+        ResourceLimits {
+            max_address_space_bytes: self.max_address_space_bytes,
+            max_cpu_seconds: self.max_cpu_seconds,
+        }
+    }
+}
+
+pub struct EngineConfig(Mutex<EngineConfigData>);
+
+impl EngineConfig {
+    /// Load from `<app-config-dir>/engine_config.json`, falling back to
+    /// built-in defaults if it's missing or fails to parse. `GUARDIAN_REDACT_PYTHON`
+    /// / `GUARDIAN_REDACT_WORKER_DIR` env vars override whatever was loaded,
+    /// for packaged installs and CI that can't pre-seed the config file.
+    pub fn load(app: &tauri::AppHandle) -> Self {
+        let mut data = config_path(app)
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|raw| serde_json::from_str(&raw).ok())
+            .unwrap_or_default();
+
+        if let Ok(python_exe) = std::env::var("GUARDIAN_REDACT_PYTHON") {
+            data.python_exe = PathBuf::from(python_exe);
+        }
+        if let Ok(worker_dir) = std::env::var("GUARDIAN_REDACT_WORKER_DIR") {
+            data.worker_dir = PathBuf::from(worker_dir);
+        }
+
+        Self(Mutex::new(data))
+    }
+
+    pub fn snapshot(&self) -> EngineConfigData {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Replace the in-memory config and persist it to `app_config_dir`, so
+    /// a pointed-at venv survives a restart. Silently skips persistence if
+    /// the app config directory can't be resolved; the in-memory value
+    /// still takes effect for the rest of this run.
+    pub fn set(&self, app: &tauri::AppHandle, data: EngineConfigData) -> Result<(), String> {
+        if let Some(path) = config_path(app) {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create config directory: {}", e))?;
+            }
+            let raw = serde_json::to_string_pretty(&data)
+                .map_err(|e| format!("Failed to serialize engine config: {}", e))?;
+            std::fs::write(&path, raw).map_err(|e| format!("Failed to write engine config: {}", e))?;
+        }
+
+        *self.0.lock().unwrap() = data;
+        Ok(())
+    }
+}
+
+fn config_path(app: &tauri::AppHandle) -> Option<PathBuf> {
+    app.path_resolver()
+        .app_config_dir()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+}
+
+/// Candidate python executables to offer the frontend when it asks us to
+/// auto-locate a runtime, cheapest/most-likely first. Only paths that
+/// actually exist on disk are returned.
+pub fn detect_candidates(worker_dir: &PathBuf) -> Vec<PathBuf> {
+    let candidates = if cfg!(windows) {
+        vec![
+            worker_dir.join(".venv/Scripts/python.exe"),
+            worker_dir.join("venv/Scripts/python.exe"),
+            PathBuf::from("C:/coding/gemma/.venv/Scripts/python.exe"),
+        ]
+    } else {
+        vec![
+            worker_dir.join(".venv/bin/python3"),
+            worker_dir.join("venv/bin/python3"),
+            PathBuf::from("/usr/bin/python3"),
+            PathBuf::from("/usr/local/bin/python3"),
+        ]
+    };
+
+    candidates.into_iter().filter(|path| path.exists()).collect()
+}
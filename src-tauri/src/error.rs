@@ -0,0 +1,72 @@
+use std::fmt;
+use std::process::ExitStatus;
+
+/// Distinct failure modes for a worker subprocess call, so the frontend can
+/// tell a cancelled job from a crashed worker from a malformed JSON parse
+/// instead of matching on an opaque error string.
+#[derive(Debug, serde::Serialize)]
+#[serde(tag = "kind", content = "data")]
+pub enum WorkerError {
+    SpawnFailed { message: String },
+    NonZeroExit { code: i32, stderr_tail: String },
+    KilledBySignal { signal: i32 },
+    Timeout,
+    InvalidJson { snippet: String },
+    Cancelled,
+    /// The shared worker is already processing another job.
+    Busy { message: String },
+    /// The user dismissed a save dialog, or the dialog callback otherwise
+    /// didn't hand back a path. Distinct from `SpawnFailed`: no subprocess
+    /// was ever involved, so the frontend shouldn't treat this like one.
+    DialogCancelled { message: String },
+}
+
+impl WorkerError {
+    /// Classify how a child exited. `status.code()` is `None` when the
+    /// child was terminated by a signal rather than exiting normally, so we
+    /// can't assume an exit code is always present.
+    pub fn from_exit_status(status: ExitStatus, stderr_tail: String) -> Self {
+        if let Some(code) = status.code() {
+            return WorkerError::NonZeroExit { code, stderr_tail };
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::process::ExitStatusExt;
+            if let Some(signal) = status.signal() {
+                return WorkerError::KilledBySignal { signal };
+            }
+        }
+
+        WorkerError::NonZeroExit { code: -1, stderr_tail }
+    }
+
+    /// Truncate a raw line to a short snippet safe to embed in an error
+    /// message, instead of dumping the whole (possibly huge) payload.
+    pub fn json_snippet(raw: &str) -> String {
+        const MAX_CHARS: usize = 200;
+        let snippet: String = raw.chars().take(MAX_CHARS).collect();
+        if raw.chars().count() > MAX_CHARS {
+            format!("{}…", snippet)
+        } else {
+            snippet
+        }
+    }
+}
+
+impl fmt::Display for WorkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WorkerError::SpawnFailed { message } => write!(f, "Failed to spawn worker: {}", message),
+            WorkerError::NonZeroExit { code, stderr_tail } => {
+                write!(f, "Worker exited with code {}: {}", code, stderr_tail)
+            }
+            WorkerError::KilledBySignal { signal } => write!(f, "Worker was killed by signal {}", signal),
+            WorkerError::Timeout => write!(f, "Worker did not respond in time"),
+            WorkerError::InvalidJson { snippet } => write!(f, "Worker produced invalid JSON: {}", snippet),
+            WorkerError::Cancelled => write!(f, "Job was cancelled"),
+            WorkerError::Busy { message } => write!(f, "{}", message),
+            WorkerError::DialogCancelled { message } => write!(f, "{}", message),
+        }
+    }
+}
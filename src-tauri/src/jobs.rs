@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Grace period given to a soft stop before a job's worker is hard-killed.
+pub const DEFAULT_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Tracks the one job currently talking to the shared `AiWorker`. There is a
+/// single resident worker process, so only one job can ever actually be
+/// in flight at a time; this is that invariant made explicit instead of a
+/// `HashSet` of "registered" ids, which could hold several ids at once and
+/// left `cancel_processing` unable to tell which of them, if any, it was
+/// really about to kill.
+///
+/// Only `AiWorker`-backed jobs (`process_single_page`, `process_audio`)
+/// register here. The one-off export scripts run through
+/// `worker::run_streaming_script` instead, which spawns its own child with
+/// no handle kept anywhere `cancel_processing` could reach — registering
+/// them here would make a cancel request look like it worked when nothing
+/// was actually killed, so they intentionally don't.
+pub struct JobRegistry {
+    active: Mutex<Option<String>>,
+    next_id: AtomicU64,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self {
+            active: Mutex::new(None),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Mint a fresh job id for `start_document_processing` to hand back to
+    /// the frontend.
+    pub fn new_job_id(&self) -> String {
+        let n = self.next_id.fetch_add(1, Ordering::SeqCst);
+        format!("job-{}", n)
+    }
+
+    /// Claim the shared worker for `job_id`. Fails if another job is
+    /// already using it, rather than letting a second job start and leave
+    /// `cancel_processing` unable to tell the two apart.
+    pub fn register(&self, job_id: &str) -> Result<(), String> {
+        let mut active = self.active.lock().unwrap();
+        if let Some(existing) = active.as_ref() {
+            return Err(format!(
+                "AI worker is already busy with job {}; try again once it finishes",
+                existing
+            ));
+        }
+        *active = Some(job_id.to_string());
+        Ok(())
+    }
+
+    /// Release the worker, but only if `job_id` is the one holding it —
+    /// a stale/late unregister for a job that already lost its claim (or
+    /// never had one) must not evict whoever holds it now.
+    pub fn unregister(&self, job_id: &str) {
+        let mut active = self.active.lock().unwrap();
+        if active.as_deref() == Some(job_id) {
+            *active = None;
+        }
+    }
+
+    /// Whether `job_id` is the job currently holding the shared worker.
+    pub fn is_running(&self, job_id: &str) -> bool {
+        self.active.lock().unwrap().as_deref() == Some(job_id)
+    }
+}
@@ -2,20 +2,40 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 mod commands;
+mod config;
+mod error;
+mod jobs;
+mod process_group;
+mod resource_limits;
+mod worker;
 
 use commands::*;
+use config::EngineConfig;
+use jobs::JobRegistry;
+use tauri::Manager;
+use worker::AiWorker;
 
 fn main() {
     tauri::Builder::default()
+        .manage(AiWorker::new())
+        .manage(JobRegistry::new())
+        .setup(|app| {
+            app.manage(EngineConfig::load(&app.handle()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             initialize_ai_engine,
+            shutdown_ai_engine,
             start_document_processing,
             process_single_page,
+            cancel_processing,
             export_redacted_document,
             save_temp_file,
             save_temp_audio,
             process_audio,
-            export_redacted_audio
+            export_redacted_audio,
+            detect_python_runtime,
+            set_engine_config
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
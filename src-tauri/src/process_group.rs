@@ -0,0 +1,111 @@
+//! Cross-platform helpers for running a child process as a killable unit
+//! together with anything it forks (ffmpeg, model subprocesses), so
+//! cancelling a job can't leave orphans behind.
+
+use std::process::Command;
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::process::CommandExt;
+    use std::process::{Child, Command};
+
+    extern "C" {
+        fn kill(pid: i32, sig: i32) -> i32;
+    }
+
+    const SIGKILL: i32 = 9;
+
+    /// Put the child in a new process group (its pid becomes the pgid) so
+    /// the whole group can be killed as one unit.
+    pub fn prepare(cmd: &mut Command) {
+        cmd.process_group(0);
+    }
+
+    /// Hard-kill the child's entire process group.
+    pub fn kill_hard(child: &Child) {
+        unsafe {
+            kill(-(child.id() as i32), SIGKILL);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod imp {
+    use std::os::windows::io::AsRawHandle;
+    use std::process::{Child, Command};
+    use windows_sys::Win32::System::JobObjects::{
+        AssignProcessToJobObject, CreateJobObjectW, SetInformationJobObject,
+        TerminateJobObject, JobObjectExtendedLimitInformation,
+        JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JOB_OBJECT_LIMIT_PROCESS_MEMORY,
+    };
+
+    /// A Job Object groups the child and anything it spawns so the whole
+    /// tree can be torn down with one call, mirroring the Unix process
+    /// group above.
+    pub struct Job(isize);
+
+    impl Job {
+        pub fn new() -> Option<Self> {
+            let handle = unsafe { CreateJobObjectW(std::ptr::null(), std::ptr::null()) };
+            if handle.is_null() {
+                None
+            } else {
+                Some(Job(handle as isize))
+            }
+        }
+
+        pub fn assign(&self, child: &Child) {
+            unsafe {
+                AssignProcessToJobObject(self.0 as _, child.as_raw_handle() as _);
+            }
+        }
+
+        /// Tear down every process in the job right now. Callers that are
+        /// about to block on `child.wait()` or join a reader thread must
+        /// call this first — relying on `Drop` doesn't work because the
+        /// job only gets dropped *after* that blocking call returns, and it
+        /// can't return while the process it's waiting on is still alive.
+        pub fn terminate(&self) {
+            unsafe {
+                TerminateJobObject(self.0 as _, 1);
+            }
+        }
+
+        /// Cap the total committed memory any process in the job may use.
+        pub fn limit_memory(&self, max_bytes: u64) {
+            let mut info: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = unsafe { std::mem::zeroed() };
+            info.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_PROCESS_MEMORY;
+            info.ProcessMemoryLimit = max_bytes as usize;
+            unsafe {
+                SetInformationJobObject(
+                    self.0 as _,
+                    JobObjectExtendedLimitInformation,
+                    &info as *const _ as *const _,
+                    std::mem::size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+                );
+            }
+        }
+    }
+
+    impl Drop for Job {
+        fn drop(&mut self) {
+            self.terminate();
+        }
+    }
+
+    pub fn prepare(_cmd: &mut Command) {}
+
+    pub fn kill_hard(_child: &Child) {
+        // No-op: there's no process group to signal on Windows. Callers
+        // must call `Job::terminate` on the Job they assigned this child
+        // to *before* blocking on anything that only unblocks once the
+        // child is dead (`wait()`, joining a reader thread).
+    }
+}
+
+pub use imp::*;
+
+#[cfg(not(any(unix, windows)))]
+pub fn prepare(_cmd: &mut Command) {}
+#[cfg(not(any(unix, windows)))]
+pub fn kill_hard(_child: &std::process::Child) {}
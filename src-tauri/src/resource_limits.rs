@@ -0,0 +1,87 @@
+//! Optional address-space / CPU-time caps applied to worker subprocesses
+//! before exec, so a runaway model invocation on an untrusted PDF can't take
+//! down the whole machine.
+
+use std::process::Command;
+
+/// Resource caps to apply to a spawned child. `None` means "no cap" for
+/// that dimension.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ResourceLimits {
+    pub max_address_space_bytes: Option<u64>,
+    pub max_cpu_seconds: Option<u64>,
+}
+
+#[cfg(unix)]
+mod imp {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    #[repr(C)]
+    struct RLimit {
+        cur: u64,
+        max: u64,
+    }
+
+    extern "C" {
+        fn setrlimit(resource: i32, rlim: *const RLimit) -> i32;
+    }
+
+    // The RLIMIT_* resource numbers are not portable across Unix flavors —
+    // e.g. macOS's RLIMIT_AS is 5, not glibc's 9. Linux's are hardcoded
+    // (avoids a dependency for the platform we actually test on); every
+    // other Unix gets its numbers from the `libc` crate instead of us
+    // guessing at them.
+    #[cfg(target_os = "linux")]
+    use self::linux::{RLIMIT_AS, RLIMIT_CPU};
+    #[cfg(target_os = "linux")]
+    mod linux {
+        pub const RLIMIT_CPU: i32 = 0;
+        pub const RLIMIT_AS: i32 = 9;
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    use libc::{RLIMIT_AS, RLIMIT_CPU};
+
+    pub fn apply(cmd: &mut Command, limits: &super::ResourceLimits) {
+        let limits = *limits;
+        if limits.max_address_space_bytes.is_none() && limits.max_cpu_seconds.is_none() {
+            return;
+        }
+
+        // Safety: the closure only calls async-signal-safe libc functions
+        // between fork and exec, as required by `pre_exec`. A `setrlimit`
+        // failure is reported by returning `Err` rather than by doing any
+        // I/O here — `pre_exec`'s `io::Result` ferries it back to the
+        // parent through the exec-failure pipe, which is what makes that
+        // safe to do from inside the child before exec.
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(bytes) = limits.max_address_space_bytes {
+                    let rlim = RLimit { cur: bytes, max: bytes };
+                    if setrlimit(RLIMIT_AS as i32, &rlim) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some(secs) = limits.max_cpu_seconds {
+                    let rlim = RLimit { cur: secs, max: secs };
+                    if setrlimit(RLIMIT_CPU as i32, &rlim) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod imp {
+    use std::process::Command;
+
+    // On Windows, memory is capped via a Job Object limit instead; see
+    // `process_group::Job::limit_memory`.
+    pub fn apply(_cmd: &mut Command, _limits: &super::ResourceLimits) {}
+}
+
+pub use imp::apply;
@@ -0,0 +1,531 @@
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+use crate::error::WorkerError;
+use crate::process_group;
+use crate::resource_limits::ResourceLimits;
+
+/// Bytes of stderr kept on each side of an elided worker log, mirroring
+/// compiletest's abbreviated reader: enough to diagnose a crash without
+/// letting a chatty worker exhaust memory.
+const STDERR_RING_CAPACITY: usize = 64 * 1024;
+
+/// A stderr log that keeps only the first and last `cap` bytes, eliding the
+/// middle once a worker has produced more output than that.
+struct AbbreviatedLog {
+    cap: usize,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    elided: usize,
+}
+
+impl AbbreviatedLog {
+    fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            elided: 0,
+        }
+    }
+
+    fn push_line(&mut self, line: &str) {
+        let mut bytes = line.as_bytes();
+        if self.head.len() < self.cap {
+            let room = self.cap - self.head.len();
+            let take = room.min(bytes.len());
+            self.head.extend_from_slice(&bytes[..take]);
+            if take == bytes.len() {
+                self.head.push(b'\n');
+                return;
+            }
+            // The rest of a line that overflows the head goes to the tail,
+            // so no byte is ever kept on both sides.
+            bytes = &bytes[take..];
+        }
+        for &b in bytes {
+            self.tail.push_back(b);
+        }
+        self.tail.push_back(b'\n');
+        while self.tail.len() > self.cap {
+            self.tail.pop_front();
+            self.elided += 1;
+        }
+    }
+
+    fn snapshot(&self) -> String {
+        let head_str = String::from_utf8_lossy(&self.head);
+        let tail_str = String::from_utf8_lossy(&self.tail.iter().copied().collect::<Vec<u8>>());
+        if self.elided > 0 {
+            format!("{}\n... [{} bytes elided] ...\n{}", head_str, self.elided, tail_str)
+        } else {
+            format!("{}{}", head_str, tail_str)
+        }
+    }
+}
+
+/// A long-lived Python worker process talking newline-delimited JSON-RPC over
+/// its stdin/stdout, modeled on the nushell plugin protocol. Holding the
+/// child resident between calls keeps the Gemma model loaded instead of
+/// reloading it from scratch on every page.
+pub struct AiWorker {
+    child: Mutex<Option<Child>>,
+    stdin: Mutex<Option<ChildStdin>>,
+    stdout: Mutex<Option<BufReader<ChildStdout>>>,
+    stderr_log: Arc<Mutex<AbbreviatedLog>>,
+    next_id: AtomicU64,
+    /// Serializes `ensure_started`'s check-then-spawn so two concurrent
+    /// commands can't both observe "not running" and each spawn their own
+    /// worker, leaking whichever one loses the race for `child`/`stdin`/
+    /// `stdout`.
+    start_lock: Mutex<()>,
+    /// Set just before a deliberate soft/hard stop, so a call that observes
+    /// the worker disappearing mid-read can report `Cancelled` instead of
+    /// misclassifying it as a crash.
+    cancelled: AtomicBool,
+    #[cfg(windows)]
+    job: Mutex<Option<process_group::Job>>,
+}
+
+impl AiWorker {
+    pub fn new() -> Self {
+        Self {
+            child: Mutex::new(None),
+            stdin: Mutex::new(None),
+            stdout: Mutex::new(None),
+            stderr_log: Arc::new(Mutex::new(AbbreviatedLog::new(STDERR_RING_CAPACITY))),
+            next_id: AtomicU64::new(1),
+            start_lock: Mutex::new(()),
+            cancelled: AtomicBool::new(false),
+            #[cfg(windows)]
+            job: Mutex::new(None),
+        }
+    }
+
+    /// Whether the worker process is alive. A worker that has exited on its
+    /// own counts as not running, so `ensure_started` respawns it.
+    pub fn is_running(&self) -> bool {
+        match self.child.lock().unwrap().as_mut() {
+            Some(child) => matches!(child.try_wait(), Ok(None)),
+            None => false,
+        }
+    }
+
+    /// The elided tail of everything the worker has written to stderr since
+    /// it was started, for diagnostics when a call fails.
+    pub fn stderr_tail(&self) -> String {
+        self.stderr_log.lock().unwrap().snapshot()
+    }
+
+    /// Spawn the worker if it isn't already running. Safe to call on every
+    /// command invocation; a resident worker is left untouched.
+    pub fn ensure_started(
+        &self,
+        python_exe: &str,
+        worker_script: &str,
+        working_dir: &str,
+        limits: ResourceLimits,
+    ) -> Result<(), WorkerError> {
+        // Hold this for the whole check-then-spawn so two concurrent
+        // callers can't both see `is_running() == false` and each spawn a
+        // worker; the second one through the lock just sees the first's
+        // already-resident child and returns immediately.
+        let _start_guard = self.start_lock.lock().unwrap();
+        if self.is_running() {
+            return Ok(());
+        }
+        self.cancelled.store(false, Ordering::SeqCst);
+
+        let mut cmd = Command::new(python_exe);
+        cmd.arg(worker_script)
+            .current_dir(working_dir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        #[cfg(windows)]
+        {
+            use std::os::windows::process::CommandExt;
+            cmd.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+
+        // Run in its own process group / Job Object so cancelling the
+        // worker reaps any grandchild processes it forks (ffmpeg, model
+        // subprocesses) instead of leaving them orphaned.
+        process_group::prepare(&mut cmd);
+        crate::resource_limits::apply(&mut cmd, &limits);
+
+        let mut child = cmd.spawn().map_err(|e| WorkerError::SpawnFailed {
+            message: format!("Failed to spawn AI worker: {}", e),
+        })?;
+
+        #[cfg(windows)]
+        {
+            if let Some(job) = process_group::Job::new() {
+                job.assign(&child);
+                if let Some(max_bytes) = limits.max_address_space_bytes {
+                    job.limit_memory(max_bytes);
+                }
+                *self.job.lock().unwrap() = Some(job);
+            }
+        }
+
+        let stdin = child.stdin.take().ok_or_else(|| WorkerError::SpawnFailed {
+            message: "Worker stdin was not piped".to_string(),
+        })?;
+        let stdout = child.stdout.take().ok_or_else(|| WorkerError::SpawnFailed {
+            message: "Worker stdout was not piped".to_string(),
+        })?;
+        let stderr = child.stderr.take().ok_or_else(|| WorkerError::SpawnFailed {
+            message: "Worker stderr was not piped".to_string(),
+        })?;
+
+        // Drain stderr on its own thread so a chatty worker never fills the
+        // pipe buffer and blocks the worker while we're blocked reading
+        // stdout for a response.
+        let stderr_log = self.stderr_log.clone();
+        thread::spawn(move || {
+            let reader = BufReader::new(stderr);
+            for line in reader.lines().flatten() {
+                stderr_log.lock().unwrap().push_line(&line);
+            }
+        });
+
+        *self.stdin.lock().unwrap() = Some(stdin);
+        *self.stdout.lock().unwrap() = Some(BufReader::new(stdout));
+        *self.child.lock().unwrap() = Some(child);
+
+        Ok(())
+    }
+
+    /// Send a JSON-RPC request and block until the matching response line
+    /// comes back, or `timeout` elapses since the request was sent. Any
+    /// `{"type":"progress",...}` line seen along the way is forwarded to
+    /// `on_progress` instead of being treated as the response; it does not
+    /// extend the timeout. On timeout the worker is killed and the next call
+    /// will respawn it.
+    pub fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+        mut on_progress: impl FnMut(&Value),
+    ) -> Result<T, WorkerError> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let request = json!({ "method": method, "params": params, "id": id });
+        let line = serde_json::to_string(&request).map_err(|e| WorkerError::SpawnFailed {
+            message: format!("Failed to serialize request to AI worker: {}", e),
+        })?;
+
+        {
+            let mut stdin_guard = self.stdin.lock().unwrap();
+            let stdin = stdin_guard.as_mut().ok_or_else(|| self.classify_exit())?;
+            if writeln!(stdin, "{}", line).is_err() || stdin.flush().is_err() {
+                return Err(self.classify_exit());
+            }
+        }
+
+        let mut timed_out = false;
+        let outcome: Result<T, WorkerError> = thread::scope(|scope| {
+            let (tx, rx) = std::sync::mpsc::channel::<Result<Value, WorkerError>>();
+
+            scope.spawn(|| {
+                let mut stdout_guard = self.stdout.lock().unwrap();
+                let stdout = match stdout_guard.as_mut() {
+                    Some(stdout) => stdout,
+                    None => {
+                        let _ = tx.send(Err(self.classify_exit()));
+                        return;
+                    }
+                };
+                loop {
+                    let mut response_line = String::new();
+                    match stdout.read_line(&mut response_line) {
+                        Ok(0) => {
+                            let _ = tx.send(Err(self.classify_exit()));
+                            return;
+                        }
+                        Ok(_) => {}
+                        Err(_) => {
+                            let _ = tx.send(Err(self.classify_exit()));
+                            return;
+                        }
+                    }
+                    match serde_json::from_str::<Value>(response_line.trim()) {
+                        Ok(value) => {
+                            if tx.send(Ok(value)).is_err() {
+                                return; // caller stopped listening (timed out)
+                            }
+                        }
+                        Err(_) => {
+                            let _ = tx.send(Err(WorkerError::InvalidJson {
+                                snippet: WorkerError::json_snippet(response_line.trim()),
+                            }));
+                            return;
+                        }
+                    }
+                }
+            });
+
+            let deadline = Instant::now() + timeout;
+            loop {
+                match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(Ok(value)) => {
+                        if value.get("type").and_then(Value::as_str) == Some("progress") {
+                            on_progress(&value);
+                            continue;
+                        }
+                        if value.get("id").and_then(Value::as_u64) != Some(id) {
+                            continue;
+                        }
+                        if let Some(error) = value.get("error") {
+                            return Err(WorkerError::InvalidJson {
+                                snippet: WorkerError::json_snippet(&error.to_string()),
+                            });
+                        }
+                        let result = value.get("result").cloned().unwrap_or(Value::Null);
+                        return serde_json::from_value(result).map_err(|e| WorkerError::InvalidJson {
+                            snippet: WorkerError::json_snippet(&e.to_string()),
+                        });
+                    }
+                    Ok(Err(e)) => return Err(e),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                        self.kill_child_only();
+                        return Err(WorkerError::Timeout);
+                    }
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+                        return Err(self.classify_exit());
+                    }
+                }
+            }
+        });
+
+        if matches!(outcome, Err(WorkerError::Timeout)) {
+            timed_out = true;
+        }
+        if timed_out {
+            self.clear_after_exit();
+        }
+
+        outcome
+    }
+
+    /// Work out why the worker process is no longer there to talk to: a
+    /// deliberate cancellation, a non-zero exit, or a signal. Consumes the
+    /// `cancelled` flag, so it only attributes one call to the cancel.
+    fn classify_exit(&self) -> WorkerError {
+        if self.cancelled.swap(false, Ordering::SeqCst) {
+            return WorkerError::Cancelled;
+        }
+        let status = self
+            .child
+            .lock()
+            .unwrap()
+            .as_mut()
+            .and_then(|child| child.try_wait().ok().flatten());
+        let stderr_tail = self.stderr_tail();
+        match status {
+            Some(status) => WorkerError::from_exit_status(status, stderr_tail),
+            None => WorkerError::NonZeroExit { code: -1, stderr_tail },
+        }
+    }
+
+    /// Hard-kill the worker's process group, if it's still running. Leaves
+    /// the `child`/`stdin`/`stdout` slots populated so a blocked reader
+    /// thread can observe EOF and exit; call `clear_after_exit` once it has.
+    fn kill_child_only(&self) {
+        // On Windows the kill happens via the Job Object, not a process-group
+        // signal, so it must be terminated explicitly before `child.wait()`
+        // below — otherwise `wait()` blocks forever on a child `kill_hard`
+        // never actually touched.
+        #[cfg(windows)]
+        {
+            if let Some(job) = self.job.lock().unwrap().as_ref() {
+                job.terminate();
+            }
+        }
+        if let Some(child) = self.child.lock().unwrap().as_mut() {
+            if matches!(child.try_wait(), Ok(None)) {
+                process_group::kill_hard(child);
+                let _ = child.wait();
+            }
+        }
+    }
+
+    /// Reset all worker handles after its process has exited, so the next
+    /// call respawns it via `ensure_started`.
+    fn clear_after_exit(&self) {
+        *self.child.lock().unwrap() = None;
+        *self.stdin.lock().unwrap() = None;
+        *self.stdout.lock().unwrap() = None;
+        #[cfg(windows)]
+        {
+            *self.job.lock().unwrap() = None;
+        }
+    }
+
+    /// Send a soft-stop method over the JSON-RPC channel, wait `stop_timeout`
+    /// for the worker to exit on its own, then escalate to killing its
+    /// process group. Shared by `shutdown` and `cancel`, which only differ
+    /// in which method they ask the worker to handle.
+    fn soft_then_hard_stop(&self, method: &str, stop_timeout: Duration) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        {
+            let mut stdin_guard = self.stdin.lock().unwrap();
+            if let Some(stdin) = stdin_guard.as_mut() {
+                let _ = writeln!(stdin, "{}", json!({ "method": method, "params": {}, "id": 0 }));
+                let _ = stdin.flush();
+            }
+        }
+
+        let deadline = Instant::now() + stop_timeout;
+        loop {
+            let still_running = match self.child.lock().unwrap().as_mut() {
+                Some(child) => matches!(child.try_wait(), Ok(None)),
+                None => false,
+            };
+            if !still_running || Instant::now() >= deadline {
+                break;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        self.kill_child_only();
+        self.clear_after_exit();
+    }
+
+    /// Ask the worker to terminate and wait for it to exit. Idempotent.
+    pub fn shutdown(&self) -> Result<(), String> {
+        self.soft_then_hard_stop("shutdown", Duration::from_secs(5));
+        Ok(())
+    }
+
+    /// Cancel whatever call is currently in flight. Sends a soft stop over
+    /// the JSON-RPC channel, waits `stop_timeout` for the worker to exit on
+    /// its own, then escalates to killing its process group. A killed
+    /// worker is not respawned here; the next command will call
+    /// `ensure_started` and get a fresh one.
+    pub fn cancel(&self, stop_timeout: Duration) -> Result<(), String> {
+        self.soft_then_hard_stop("cancel", stop_timeout);
+        Ok(())
+    }
+}
+
+/// Spawn a one-off Python script with piped stdout/stderr, forwarding
+/// newline-delimited `{"type":"progress",...}` records via `on_progress` and
+/// resolving once a `{"type":"result","result":...}` line arrives. Stdout
+/// and stderr are read concurrently on separate threads to avoid the classic
+/// pipe-buffer deadlock where filling one pipe while blocked reading the
+/// other hangs both sides.
+pub fn run_streaming_script<T: DeserializeOwned>(
+    mut cmd: Command,
+    limits: ResourceLimits,
+    timeout: Duration,
+    mut on_progress: impl FnMut(&Value),
+) -> Result<T, WorkerError> {
+    cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+    process_group::prepare(&mut cmd);
+    crate::resource_limits::apply(&mut cmd, &limits);
+
+    let mut child = cmd.spawn().map_err(|e| WorkerError::SpawnFailed {
+        message: format!("Failed to spawn script: {}", e),
+    })?;
+
+    #[cfg(windows)]
+    let job = process_group::Job::new().map(|job| {
+        job.assign(&child);
+        if let Some(max_bytes) = limits.max_address_space_bytes {
+            job.limit_memory(max_bytes);
+        }
+        job
+    });
+
+    let stdout = child.stdout.take().ok_or_else(|| WorkerError::SpawnFailed {
+        message: "Script stdout was not piped".to_string(),
+    })?;
+    let stderr = child.stderr.take().ok_or_else(|| WorkerError::SpawnFailed {
+        message: "Script stderr was not piped".to_string(),
+    })?;
+
+    let (tx, rx) = std::sync::mpsc::channel::<Value>();
+    let stdout_thread = thread::spawn(move || {
+        let reader = BufReader::new(stdout);
+        for line in reader.lines().flatten() {
+            if let Ok(value) = serde_json::from_str::<Value>(&line) {
+                if tx.send(value).is_err() {
+                    break;
+                }
+            }
+        }
+    });
+
+    let stderr_log = Arc::new(Mutex::new(AbbreviatedLog::new(STDERR_RING_CAPACITY)));
+    let stderr_log_writer = stderr_log.clone();
+    let stderr_thread = thread::spawn(move || {
+        let reader = BufReader::new(stderr);
+        for line in reader.lines().flatten() {
+            stderr_log_writer.lock().unwrap().push_line(&line);
+        }
+    });
+
+    let mut final_result: Option<Result<T, WorkerError>> = None;
+    let mut timed_out = false;
+    let deadline = Instant::now() + timeout;
+    loop {
+        match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(value) => match value.get("type").and_then(Value::as_str) {
+                Some("progress") => on_progress(&value),
+                Some("result") => {
+                    let result = value.get("result").cloned().unwrap_or(Value::Null);
+                    final_result = Some(serde_json::from_value(result).map_err(|e| {
+                        WorkerError::InvalidJson {
+                            snippet: WorkerError::json_snippet(&e.to_string()),
+                        }
+                    }));
+                    break;
+                }
+                _ => {}
+            },
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                timed_out = true;
+                // Terminate the Job first on Windows: the threads joined
+                // below are blocked reading pipes from `child`, and they
+                // can't see EOF until it's actually dead.
+                #[cfg(windows)]
+                if let Some(job) = &job {
+                    job.terminate();
+                }
+                process_group::kill_hard(&child);
+                break;
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+        }
+    }
+
+    let _ = stdout_thread.join();
+    let _ = stderr_thread.join();
+    let stderr_tail = stderr_log.lock().unwrap().snapshot();
+    let status = child.wait().map_err(|e| WorkerError::SpawnFailed {
+        message: format!("Failed to wait on script: {}", e),
+    })?;
+
+    if timed_out {
+        return Err(WorkerError::Timeout);
+    }
+
+    match final_result {
+        Some(result) => result,
+        None if status.success() => Err(WorkerError::InvalidJson {
+            snippet: "script exited without a result line".to_string(),
+        }),
+        None => Err(WorkerError::from_exit_status(status, stderr_tail)),
+    }
+}